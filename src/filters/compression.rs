@@ -2,14 +2,23 @@
 //!
 //! Filters that compress the body of a response.
 
-use async_compression::stream::{BrotliEncoder, DeflateEncoder, GzipEncoder};
+use async_compression::stream::{BrotliEncoder, DeflateEncoder, GzipEncoder, ZstdEncoder};
+pub use async_compression::Level;
+use brotli::CompressorWriter as SyncBrotliEncoder;
+use bytes::Bytes;
+use flate2::write::{DeflateEncoder as SyncDeflateEncoder, GzEncoder as SyncGzEncoder};
 use headers::ContentCoding;
 use http::header::HeaderValue;
+use http::StatusCode;
 use hyper::{
-    header::{CONTENT_ENCODING, CONTENT_LENGTH},
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
     Body,
 };
-use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use zstd::stream::write::Encoder as SyncZstdEncoder;
 
 use crate::filter::{Filter, WrapSealed};
 use crate::reject::IsReject;
@@ -17,10 +26,262 @@ use crate::reply::{Reply, Response};
 
 use self::internal::{CompressionProps, WithCompression};
 
+/// The minimum `Content-Length` (in bytes) a response must have before it is considered
+/// for compression, unless overridden with [`Compression::min_size`].
+const DEFAULT_MIN_SIZE: usize = 32;
+
 /// Compression
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone)]
 pub struct Compression<F> {
     func: F,
+    min_size: usize,
+    level: Level,
+    blocking: bool,
+    content_type_filter: Option<Arc<dyn Fn(&str) -> bool + Send + Sync>>,
+}
+
+impl<F> std::fmt::Debug for Compression<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Compression")
+            .field("min_size", &self.min_size)
+            .field("level", &self.level)
+            .field("blocking", &self.blocking)
+            .finish()
+    }
+}
+
+impl<F> Compression<F> {
+    /// Only compress responses whose `Content-Length` is at least `min_size` bytes.
+    ///
+    /// Defaults to 32 bytes; responses smaller than that rarely benefit from compression
+    /// and aren't worth the CPU. Responses without a parsable `Content-Length` header are not
+    /// checked against this minimum and remain eligible for compression.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Set the compression level/quality passed to the underlying encoder.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use warp::compression::Level;
+    /// use warp::Filter;
+    ///
+    /// let route = warp::get()
+    ///     .and(warp::path::end())
+    ///     .and(warp::fs::file("./README.md"))
+    ///     .with(warp::compression::brotli().level(Level::Best));
+    /// ```
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Run the encoder on a [`tokio::task::spawn_blocking`] thread instead of inline on the
+    /// async executor.
+    ///
+    /// The compressor reads chunks off the reactor and writes compressed `Bytes` back through
+    /// a channel into the response body, keeping CPU-heavy compression (e.g. high brotli
+    /// quality over a large file) from stalling the runtime.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use warp::Filter;
+    ///
+    /// let route = warp::get()
+    ///     .and(warp::path::end())
+    ///     .and(warp::fs::file("./README.md"))
+    ///     .with(warp::compression::brotli().blocking(true));
+    /// ```
+    pub fn blocking(mut self, blocking: bool) -> Self {
+        self.blocking = blocking;
+        self
+    }
+
+    /// Only compress responses whose `Content-Type` passes the given predicate.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use warp::Filter;
+    ///
+    /// let route = warp::get()
+    ///     .and(warp::path::end())
+    ///     .and(warp::fs::file("./README.md"))
+    ///     .with(warp::compression::gzip().content_type_filter(|content_type| {
+    ///         content_type.starts_with("text/") || content_type == "application/json"
+    ///     }));
+    /// ```
+    pub fn content_type_filter<P>(mut self, predicate: P) -> Self
+    where
+        P: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.content_type_filter = Some(Arc::new(predicate));
+        self
+    }
+}
+
+/// Whether a response should be compressed, given its parts and the configured predicate.
+///
+/// Refuses compression when the response already carries a `Content-Encoding` (it's already
+/// compressed, e.g. a pre-gzipped asset served by [`warp::fs`](crate::filters::fs)), when the
+/// status code can't carry a body worth compressing, when the body is smaller than `min_size`,
+/// or when the `Content-Type` is rejected by `content_type_filter`.
+fn should_compress(
+    head: &http::response::Parts,
+    min_size: usize,
+    content_type_filter: &Option<Arc<dyn Fn(&str) -> bool + Send + Sync>>,
+) -> bool {
+    if head.headers.contains_key(CONTENT_ENCODING) {
+        return false;
+    }
+
+    match head.status {
+        StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED | StatusCode::SWITCHING_PROTOCOLS => {
+            return false
+        }
+        _ => {}
+    }
+
+    if let Some(len) = head
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+    {
+        if len < min_size {
+            return false;
+        }
+    }
+
+    if let Some(filter) = content_type_filter {
+        let content_type = head
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if !filter(content_type) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// A [`Write`] sink that forwards each write as a `Bytes` chunk over a channel, so a
+/// synchronous, `Write`-based encoder running on a blocking thread can feed chunks back
+/// into an async response [`Body`].
+struct ChannelWriter {
+    tx: mpsc::Sender<std::io::Result<Bytes>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tx
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`Write`]-based encoder that needs an explicit finishing step (writing a trailer,
+/// checksum, or final frame) once all input has been written, rather than relying on `flush`.
+trait FinishEncoder: Write {
+    fn finish_encoder(self) -> std::io::Result<()>;
+}
+
+impl<W: Write> FinishEncoder for SyncGzEncoder<W> {
+    fn finish_encoder(self) -> std::io::Result<()> {
+        self.finish().map(|_| ())
+    }
+}
+
+impl<W: Write> FinishEncoder for SyncDeflateEncoder<W> {
+    fn finish_encoder(self) -> std::io::Result<()> {
+        self.finish().map(|_| ())
+    }
+}
+
+impl<W: Write> FinishEncoder for SyncBrotliEncoder<W> {
+    fn finish_encoder(mut self) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> FinishEncoder for SyncZstdEncoder<'_, W> {
+    fn finish_encoder(self) -> std::io::Result<()> {
+        self.finish().map(|_| ())
+    }
+}
+
+/// Runs `new_encoder` on a [`tokio::task::spawn_blocking`] thread, feeding it the chunks of
+/// `body` and streaming its compressed output back out as the response [`Body`].
+fn compress_blocking<E>(
+    body: internal::CompressableBody<Body, hyper::Error>,
+    new_encoder: impl FnOnce(ChannelWriter) -> E + Send + 'static,
+) -> Body
+where
+    E: FinishEncoder + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(16);
+
+    tokio::task::spawn_blocking(move || {
+        let err_tx = tx.clone();
+        let mut encoder = new_encoder(ChannelWriter { tx });
+        for chunk in futures::executor::block_on_stream(body) {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let _ = err_tx.blocking_send(Err(err));
+                    return;
+                }
+            };
+            if let Err(err) = encoder.write_all(&chunk) {
+                let _ = err_tx.blocking_send(Err(err));
+                return;
+            }
+        }
+        let _ = encoder.finish_encoder();
+    });
+
+    Body::wrap_stream(ReceiverStream::new(rx))
+}
+
+/// Maps a [`Level`] onto the `0..=9` scale used by [`flate2::Compression`].
+fn flate2_level(level: Level) -> flate2::Compression {
+    match level {
+        Level::Fastest => flate2::Compression::fast(),
+        Level::Best => flate2::Compression::best(),
+        Level::Precise(q) => flate2::Compression::new(q.clamp(0, 9) as u32),
+        Level::Default => flate2::Compression::default(),
+    }
+}
+
+/// Maps a [`Level`] onto the `0..=11` quality scale used by the `brotli` crate's encoder.
+fn brotli_quality(level: Level) -> u32 {
+    match level {
+        Level::Fastest => 1,
+        Level::Best => 11,
+        Level::Precise(q) => q.clamp(0, 11) as u32,
+        Level::Default => 11,
+    }
+}
+
+/// Maps a [`Level`] onto the `1..=22` scale used by the `zstd` crate's encoder.
+fn zstd_level(level: Level) -> i32 {
+    match level {
+        Level::Fastest => 1,
+        Level::Best => 21,
+        Level::Precise(q) => q.clamp(1, 22),
+        Level::Default => zstd::DEFAULT_COMPRESSION_LEVEL,
+    }
 }
 
 // TODO: The implementation of `gzip()`, `deflate()`, and `brotli()` could be replaced with
@@ -30,6 +291,11 @@ pub struct Compression<F> {
 /// using whatever value is specified in the `Accept-Encoding` header, adding
 /// `content-encoding: <coding>` to the Response's [`HeaderMap`](hyper::HeaderMap)
 ///
+/// Honors `q` values, `identity`, and `*` per [RFC 7231 §5.3.4](https://httpwg.org/specs/rfc7231.html#header.accept-encoding),
+/// preferring `br` over `zstd` over `gzip` over `deflate` when multiple codings tie on `q`.
+/// Responds `406 Not Acceptable` when the client's `Accept-Encoding` rules out every coding
+/// we can produce, including `identity`.
+///
 /// # Example
 ///
 /// ```
@@ -42,30 +308,127 @@ pub struct Compression<F> {
 /// ```
 pub fn auto() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
     let func = move |props: CompressionProps| {
-        if let Some(ref header) = props.accept_enc {
-            if let Some(encoding) = header.prefered_encoding() {
-                return match encoding {
-                    ContentCoding::GZIP => (gzip().func)(props),
-                    ContentCoding::DEFLATE => (deflate().func)(props),
-                    ContentCoding::BROTLI => (brotli().func)(props),
-                    _ => Response::from_parts(props.head, Body::wrap_stream(props.body)),
-                };
+        let header = props
+            .accept_enc_header
+            .as_ref()
+            .and_then(|val| val.to_str().ok());
+
+        match negotiate(header) {
+            Negotiated::Coding("br") => (brotli().func)(props),
+            Negotiated::Coding("zstd") => (zstd().func)(props),
+            Negotiated::Coding("gzip") => (gzip().func)(props),
+            Negotiated::Coding("deflate") => (deflate().func)(props),
+            Negotiated::Coding(coding) => unreachable!("unsupported coding negotiated: {}", coding),
+            Negotiated::Identity => Response::from_parts(props.head, Body::wrap_stream(props.body)),
+            Negotiated::NotAcceptable => {
+                let mut head = props.head;
+                // The body is being replaced with an empty one, so the old length no
+                // longer applies; leaving it would desync clients expecting that many bytes.
+                head.headers.remove(CONTENT_LENGTH);
+                let mut resp = Response::from_parts(head, Body::empty());
+                *resp.status_mut() = StatusCode::NOT_ACCEPTABLE;
+                resp
             }
         }
-        Response::from_parts(props.head, Body::wrap_stream(props.body))
     };
 
-    Compression { func }
+    Compression {
+        func,
+        min_size: DEFAULT_MIN_SIZE,
+        level: Level::Default,
+        blocking: false,
+        content_type_filter: None,
+    }
+}
+
+/// The codings `auto()` can produce, in descending preference order used to break ties
+/// between entries that share the same `q` value.
+const SUPPORTED_CODINGS: &[&str] = &["br", "zstd", "gzip", "deflate"];
+
+/// One `coding[;q=value]` entry parsed out of an `Accept-Encoding` header.
+struct AcceptEncodingEntry<'a> {
+    coding: &'a str,
+    q: f32,
+}
+
+/// Parses an `Accept-Encoding` header value into its individual coding/`q` entries.
+/// Entries with an unparsable `q` default to `q=1`, matching the RFC 7231 default.
+fn parse_accept_encoding(header: &str) -> Vec<AcceptEncodingEntry<'_>> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let coding = parts.next()?.trim();
+            if coding.is_empty() {
+                return None;
+            }
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some(AcceptEncodingEntry { coding, q })
+        })
+        .collect()
+}
+
+/// The outcome of negotiating a content-coding against an `Accept-Encoding` header.
+#[derive(Debug, PartialEq)]
+enum Negotiated {
+    /// Use one of `SUPPORTED_CODINGS`.
+    Coding(&'static str),
+    /// Send the body uncompressed.
+    Identity,
+    /// None of our codings (including `identity`) are acceptable to the client.
+    NotAcceptable,
 }
 
-/// Given an optional existing encoding header, appends to the existing or creates a new one
-fn create_encoding_header(existing: Option<HeaderValue>, coding: ContentCoding) -> HeaderValue {
-    if let Some(val) = existing {
-        if let Ok(str_val) = val.to_str() {
-            return HeaderValue::try_from(&format!("{}, {}", coding.to_string(), str_val))
-                .unwrap_or_else(|_| coding.into());
+/// Picks a content-coding for `auto()`, honoring `q` values, `identity`, and `*`.
+fn negotiate(header: Option<&str>) -> Negotiated {
+    let header = match header {
+        Some(header) => header,
+        None => return Negotiated::Identity,
+    };
+
+    let entries = parse_accept_encoding(header);
+    let wildcard_q = entries.iter().find(|e| e.coding == "*").map(|e| e.q);
+
+    let mut best: Option<(&'static str, f32)> = None;
+    for &coding in SUPPORTED_CODINGS {
+        let q = entries
+            .iter()
+            .find(|e| e.coding.eq_ignore_ascii_case(coding))
+            .map(|e| e.q)
+            .or(wildcard_q);
+
+        if let Some(q) = q {
+            if q <= 0.0 {
+                continue;
+            }
+            if best.map_or(true, |(_, best_q)| q > best_q) {
+                best = Some((coding, q));
+            }
         }
     }
+
+    // `*` never governs `identity`'s acceptability; only an explicit `identity` entry does.
+    let identity_q = entries
+        .iter()
+        .find(|e| e.coding.eq_ignore_ascii_case("identity"))
+        .map(|e| e.q)
+        .unwrap_or(1.0);
+
+    match best {
+        Some((coding, q)) if q >= identity_q => Negotiated::Coding(coding),
+        _ if identity_q > 0.0 => Negotiated::Identity,
+        _ => Negotiated::NotAcceptable,
+    }
+}
+
+/// Builds the `Content-Encoding` header value for `coding`.
+///
+/// `should_compress` already refuses to compress a response that carries a `Content-Encoding`,
+/// so there's never an existing value to layer onto here.
+fn create_encoding_header(coding: ContentCoding) -> HeaderValue {
     coding.into()
 }
 
@@ -84,16 +447,24 @@ fn create_encoding_header(existing: Option<HeaderValue>, coding: ContentCoding)
 /// ```
 pub fn gzip() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
     let func = move |mut props: CompressionProps| {
-        let body = Body::wrap_stream(GzipEncoder::new(props.body));
-        let header = create_encoding_header(
-            props.head.headers.remove(CONTENT_ENCODING),
-            ContentCoding::GZIP,
-        );
+        let level = props.level;
+        let body = if props.blocking {
+            compress_blocking(props.body, move |w| SyncGzEncoder::new(w, flate2_level(level)))
+        } else {
+            Body::wrap_stream(GzipEncoder::with_quality(props.body, level))
+        };
+        let header = create_encoding_header(ContentCoding::GZIP);
         props.head.headers.append(CONTENT_ENCODING, header);
         props.head.headers.remove(CONTENT_LENGTH);
         Response::from_parts(props.head, body)
     };
-    Compression { func }
+    Compression {
+        func,
+        min_size: DEFAULT_MIN_SIZE,
+        level: Level::Default,
+        blocking: false,
+        content_type_filter: None,
+    }
 }
 
 /// Create a wrapping filter that compresses the Body of a [`Response`](crate::reply::Response)
@@ -111,18 +482,28 @@ pub fn gzip() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
 /// ```
 pub fn deflate() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
     let func = move |mut props: CompressionProps| {
-        let body = Body::wrap_stream(DeflateEncoder::new(props.body));
-
-        let header = create_encoding_header(
-            props.head.headers.remove(CONTENT_ENCODING),
-            ContentCoding::DEFLATE,
-        );
+        let level = props.level;
+        let body = if props.blocking {
+            compress_blocking(props.body, move |w| {
+                SyncDeflateEncoder::new(w, flate2_level(level))
+            })
+        } else {
+            Body::wrap_stream(DeflateEncoder::with_quality(props.body, level))
+        };
+
+        let header = create_encoding_header(ContentCoding::DEFLATE);
         props.head.headers.append(CONTENT_ENCODING, header);
         props.head.headers.remove(CONTENT_LENGTH);
 
         Response::from_parts(props.head, body)
     };
-    Compression { func }
+    Compression {
+        func,
+        min_size: DEFAULT_MIN_SIZE,
+        level: Level::Default,
+        blocking: false,
+        content_type_filter: None,
+    }
 }
 
 /// Create a wrapping filter that compresses the Body of a [`Response`](crate::reply::Response)
@@ -140,17 +521,72 @@ pub fn deflate() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
 /// ```
 pub fn brotli() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
     let func = move |mut props: CompressionProps| {
-        let body = Body::wrap_stream(BrotliEncoder::new(props.body));
+        let level = props.level;
+        let body = if props.blocking {
+            compress_blocking(props.body, move |w| {
+                SyncBrotliEncoder::new(w, 4096, brotli_quality(level), 22)
+            })
+        } else {
+            Body::wrap_stream(BrotliEncoder::with_quality(props.body, level))
+        };
         props.head.headers.remove(CONTENT_LENGTH);
 
-        let header = create_encoding_header(
-            props.head.headers.remove(CONTENT_ENCODING),
-            ContentCoding::BROTLI,
-        );
+        let header = create_encoding_header(ContentCoding::BROTLI);
         props.head.headers.append(CONTENT_ENCODING, header);
         Response::from_parts(props.head, body)
     };
-    Compression { func }
+    Compression {
+        func,
+        min_size: DEFAULT_MIN_SIZE,
+        level: Level::Default,
+        blocking: false,
+        content_type_filter: None,
+    }
+}
+
+/// Create a wrapping filter that compresses the Body of a [`Response`](crate::reply::Response)
+/// using zstd, adding `content-encoding: zstd` to the Response's [`HeaderMap`](hyper::HeaderMap)
+///
+/// # Example
+///
+/// ```
+/// use warp::Filter;
+///
+/// let route = warp::get()
+///     .and(warp::path::end())
+///     .and(warp::fs::file("./README.md"))
+///     .with(warp::compression::zstd());
+/// ```
+pub fn zstd() -> Compression<impl Fn(CompressionProps) -> Response + Copy> {
+    let func = move |mut props: CompressionProps| {
+        let level = props.level;
+        let body = if props.blocking {
+            compress_blocking(props.body, move |w| {
+                SyncZstdEncoder::new(w, zstd_level(level)).expect("zstd encoder init")
+            })
+        } else {
+            Body::wrap_stream(ZstdEncoder::with_quality(props.body, level))
+        };
+        props.head.headers.remove(CONTENT_LENGTH);
+
+        // `ContentCoding` has no `zstd` variant, so the header value is built by hand.
+        let header = create_encoding_header_str("zstd");
+        props.head.headers.append(CONTENT_ENCODING, header);
+
+        Response::from_parts(props.head, body)
+    };
+    Compression {
+        func,
+        min_size: DEFAULT_MIN_SIZE,
+        level: Level::Default,
+        blocking: false,
+        content_type_filter: None,
+    }
+}
+
+/// Like [`create_encoding_header`], but for codings that don't have a [`ContentCoding`] variant.
+fn create_encoding_header_str(coding: &'static str) -> HeaderValue {
+    HeaderValue::from_static(coding)
 }
 
 impl<FN, F> WrapSealed<F> for Compression<FN>
@@ -177,7 +613,6 @@ mod internal {
 
     use bytes::Bytes;
     use futures::{ready, Stream, TryFuture};
-    use headers::HeaderMapExt;
     use hyper::Body;
     use pin_project::pin_project;
 
@@ -229,7 +664,11 @@ mod internal {
     pub struct CompressionProps {
         pub(super) body: CompressableBody<Body, hyper::Error>,
         pub(super) head: http::response::Parts,
-        pub(super) accept_enc: Option<headers::AcceptEncoding>,
+        /// The raw `Accept-Encoding` header value. Negotiation is done against this
+        /// directly, since `headers::AcceptEncoding` understands neither `zstd` nor `q` values.
+        pub(super) accept_enc_header: Option<http::header::HeaderValue>,
+        pub(super) level: super::Level,
+        pub(super) blocking: bool,
     }
 
     #[allow(missing_debug_implementations)]
@@ -243,7 +682,7 @@ mod internal {
     }
 
     #[allow(missing_debug_implementations)]
-    #[derive(Clone, Copy)]
+    #[derive(Clone)]
     pub struct WithCompression<FN, F> {
         pub(super) compress: Compression<FN>,
         pub(super) filter: F,
@@ -291,12 +730,24 @@ mod internal {
             match result {
                 Ok(reply) => {
                     let resp = route::with(|route| {
-                        let acc_enc: Option<headers::AcceptEncoding> = route.headers().typed_get();
                         let (head, body) = reply.into_response().into_parts();
+
+                        if !super::should_compress(
+                            &head,
+                            self.compress.min_size,
+                            &self.compress.content_type_filter,
+                        ) {
+                            return Response::from_parts(head, body);
+                        }
+
+                        let acc_enc_header =
+                            route.headers().get(hyper::header::ACCEPT_ENCODING).cloned();
                         let compress_props = CompressionProps {
                             body: body.into(),
                             head: head,
-                            accept_enc: acc_enc,
+                            accept_enc_header: acc_enc_header,
+                            level: self.compress.level,
+                            blocking: self.compress.blocking,
                         };
                         (self.compress.func)(compress_props)
                     });
@@ -307,3 +758,116 @@ mod internal {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, should_compress, Negotiated};
+    use http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+    use http::StatusCode;
+    use std::sync::Arc;
+
+    fn parts(builder: http::response::Builder) -> http::response::Parts {
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn prefers_br_over_gzip() {
+        assert_eq!(negotiate(Some("gzip, br")), Negotiated::Coding("br"));
+    }
+
+    #[test]
+    fn breaks_ties_by_preference_order() {
+        // gzip and zstd tie at q=0.8 (with identity explicitly pinned to the same q so it
+        // doesn't win by default); zstd comes first in `SUPPORTED_CODINGS`.
+        assert_eq!(
+            negotiate(Some("identity;q=0.8, gzip;q=0.8, zstd;q=0.8")),
+            Negotiated::Coding("zstd")
+        );
+    }
+
+    #[test]
+    fn identity_q_zero_alone_is_not_acceptable() {
+        assert_eq!(negotiate(Some("identity;q=0")), Negotiated::NotAcceptable);
+    }
+
+    #[test]
+    fn everything_forbidden_is_not_acceptable() {
+        assert_eq!(
+            negotiate(Some("gzip;q=0, identity;q=0")),
+            Negotiated::NotAcceptable
+        );
+    }
+
+    #[test]
+    fn wildcard_applies_to_unlisted_codings_not_identity() {
+        // `*` makes zstd/gzip/deflate acceptable at q=0.5, which beats the explicitly
+        // lower-q `br`, but never overrides `identity`'s default q=1.
+        assert_eq!(
+            negotiate(Some("br;q=0.1, *;q=0.5")),
+            Negotiated::Identity
+        );
+    }
+
+    #[test]
+    fn missing_header_is_identity() {
+        assert_eq!(negotiate(None), Negotiated::Identity);
+    }
+
+    #[test]
+    fn skips_when_content_encoding_already_present() {
+        let head = parts(http::Response::builder().header(CONTENT_ENCODING, "gzip"));
+        assert!(!should_compress(&head, 0, &None));
+    }
+
+    #[test]
+    fn skips_statuses_that_cant_carry_a_compressed_body() {
+        for status in [
+            StatusCode::NO_CONTENT,
+            StatusCode::NOT_MODIFIED,
+            StatusCode::SWITCHING_PROTOCOLS,
+        ] {
+            let head = parts(http::Response::builder().status(status));
+            assert!(!should_compress(&head, 0, &None));
+        }
+    }
+
+    #[test]
+    fn skips_bodies_below_min_size() {
+        let head = parts(http::Response::builder().header(CONTENT_LENGTH, "10"));
+        assert!(!should_compress(&head, 100, &None));
+    }
+
+    #[test]
+    fn skips_when_content_type_filter_rejects() {
+        let head = parts(http::Response::builder().header(CONTENT_TYPE, "image/png"));
+        let filter: Option<Arc<dyn Fn(&str) -> bool + Send + Sync>> =
+            Some(Arc::new(|content_type: &str| content_type.starts_with("text/")));
+        assert!(!should_compress(&head, 0, &filter));
+    }
+
+    #[test]
+    fn compresses_by_default() {
+        let head = parts(http::Response::builder().header(CONTENT_LENGTH, "1000"));
+        assert!(should_compress(&head, 100, &None));
+    }
+
+    #[tokio::test]
+    async fn compress_blocking_surfaces_stream_errors() {
+        use bytes::Bytes;
+        use futures::StreamExt;
+        use std::io;
+
+        let err = io::Error::new(io::ErrorKind::Other, "boom");
+        let broken = super::Body::wrap_stream(futures::stream::iter(vec![Err::<Bytes, _>(err)]));
+        let mut output = super::compress_blocking(broken.into(), |w| {
+            super::SyncGzEncoder::new(w, super::flate2_level(super::Level::Default))
+        });
+
+        let chunk = output.next().await;
+        assert!(
+            matches!(chunk, Some(Err(_))),
+            "expected an error chunk, got {:?}",
+            chunk
+        );
+    }
+}